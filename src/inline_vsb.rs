@@ -0,0 +1,275 @@
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+use std::{
+    alloc::{alloc, dealloc, handle_alloc_error, realloc, Layout},
+    marker::PhantomData,
+    mem::{align_of, size_of, MaybeUninit},
+    ptr::copy_nonoverlapping,
+    slice::{from_raw_parts, from_raw_parts_mut},
+};
+
+/// `N` bytes of storage aligned as `T` requires. The zero-length `[T; 0]` field never holds a
+/// `T`; it exists purely to force this struct's alignment to `align_of::<T>()`.
+#[repr(C)]
+struct Aligned<T, const N: usize> {
+    _align: [T; 0],
+    bytes: [MaybeUninit<u8>; N],
+}
+
+enum Storage<T, const N: usize> {
+    Inline(Aligned<T, N>),
+    Heap(*mut T),
+}
+
+/// Like [`crate::vsb::VariableSizedBox`], but stores up to `N` bytes inline instead of always
+/// allocating. Only once a requested size exceeds `N` does it spill to the global allocator,
+/// transparently switching back and forth as [`InlineVariableSizedBox::resize`] is called. This
+/// avoids allocator traffic for FFI structs whose variable tail is typically small.
+pub struct InlineVariableSizedBox<T, const N: usize> {
+    size: usize,
+    storage: Storage<T, N>,
+    pd: PhantomData<T>,
+}
+impl<T, const N: usize> InlineVariableSizedBox<T, N> {
+    /// The size is specified in bytes. The data is uninitialized.
+    pub fn new(size: usize) -> InlineVariableSizedBox<T, N> {
+        let storage = if size <= N {
+            Storage::Inline(Aligned {
+                _align: [],
+                bytes: [MaybeUninit::uninit(); N],
+            })
+        } else {
+            let layout = Layout::from_size_align(size, align_of::<T>()).unwrap();
+            let data = unsafe { alloc(layout) };
+            if data.is_null() {
+                handle_alloc_error(layout)
+            }
+            Storage::Heap(data.cast())
+        };
+        InlineVariableSizedBox {
+            size,
+            storage,
+            pd: PhantomData,
+        }
+    }
+    /// Use this to get a pointer to pass to FFI functions.
+    pub fn as_ptr(&self) -> *const T {
+        match &self.storage {
+            Storage::Inline(a) => a.bytes.as_ptr().cast(),
+            Storage::Heap(data) => *data,
+        }
+    }
+    /// Use this to get a pointer to pass to FFI functions.
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        match &mut self.storage {
+            Storage::Inline(a) => a.bytes.as_mut_ptr().cast(),
+            Storage::Heap(data) => *data,
+        }
+    }
+    /// This is used to more safely access the fixed size fields.
+    /// # Safety
+    /// The current data must be valid for an instance of `T`.
+    pub unsafe fn as_ref(&self) -> &T {
+        &*self.as_ptr()
+    }
+    /// This is used to more safely access the fixed size fields.
+    /// # Safety
+    /// The current data must be valid for an instance of `T`.
+    pub unsafe fn as_mut_ref(&mut self) -> &mut T {
+        &mut *self.as_mut_ptr()
+    }
+    /// The size is specified in bytes.
+    /// If this grows the allocation, the extra bytes will be uninitialized.
+    /// Spills to the global allocator if `size` no longer fits inline, and moves back to inline
+    /// storage if it shrinks back within `N`.
+    pub fn resize(&mut self, size: usize) {
+        match &mut self.storage {
+            Storage::Inline(_) if size <= N => {}
+            Storage::Inline(a) => {
+                let layout = Layout::from_size_align(size, align_of::<T>()).unwrap();
+                let data = unsafe { alloc(layout) };
+                if data.is_null() {
+                    handle_alloc_error(layout)
+                }
+                unsafe {
+                    copy_nonoverlapping(a.bytes.as_ptr().cast::<u8>(), data, self.size);
+                }
+                self.storage = Storage::Heap(data.cast());
+            }
+            Storage::Heap(data) if size <= N => {
+                let mut a = Aligned {
+                    _align: [],
+                    bytes: [MaybeUninit::uninit(); N],
+                };
+                unsafe {
+                    copy_nonoverlapping(data.cast::<u8>(), a.bytes.as_mut_ptr().cast(), size);
+                    dealloc(
+                        data.cast(),
+                        Layout::from_size_align(self.size, align_of::<T>()).unwrap(),
+                    );
+                }
+                self.storage = Storage::Inline(a);
+            }
+            Storage::Heap(data) => {
+                let layout = Layout::from_size_align(self.size, align_of::<T>()).unwrap();
+                let new_data = unsafe { realloc(data.cast(), layout, size) };
+                if new_data.is_null() {
+                    handle_alloc_error(layout)
+                }
+                *data = new_data.cast();
+            }
+        }
+        self.size = size;
+    }
+    /// The length of the allocation specified in bytes.
+    pub fn len(&self) -> usize {
+        self.size
+    }
+    /// Given a pointer to a specific field, upgrades the provenance of the pointer to the entire
+    /// allocation to work around stacked borrows.
+    /// # Safety
+    /// `o` must be a valid pointer within the allocation contained by this box.
+    pub unsafe fn sanitize_ptr<U>(&self, o: *const U) -> *const U {
+        let base = self.as_ptr();
+        let offset = o as isize - base as isize;
+        (base as *const u8).offset(offset).cast()
+    }
+    /// Given a pointer to a specific field, upgrades the provenance of the pointer to the entire
+    /// allocation to work around stacked borrows.
+    /// # Safety
+    /// `o` must be a valid pointer within the allocation contained by this box.
+    pub unsafe fn sanitize_mut_ptr<U>(&mut self, o: *mut U) -> *mut U {
+        let base = self.as_mut_ptr();
+        let offset = o as isize - base as isize;
+        (base as *mut u8).offset(offset).cast()
+    }
+    /// Given a pointer to a variable sized array field and the length of the array in elements,
+    /// returns a slice to the entire variable sized array.
+    /// # Safety
+    /// The slice as specified by `o` and `count` must be entirely within the allocation
+    /// contained by this box, and the data must be valid for the specified type.
+    pub unsafe fn slice_from_count<U>(&self, o: *const U, count: usize) -> &[U] {
+        let ptr = self.sanitize_ptr(o);
+        let base: *const T = self.as_ptr();
+        assert!(ptr >= base.cast());
+        assert!(count.saturating_mul(size_of::<U>()) <= self.size);
+        assert!(ptr.wrapping_add(count) <= base.cast::<u8>().add(self.size).cast());
+        from_raw_parts(ptr, count)
+    }
+    /// Given a pointer to a variable sized array field and the length of the array in elements,
+    /// returns a mutable slice to the entire variable sized array.
+    /// # Safety
+    /// The slice as specified by `o` and `count` must be entirely within the allocation
+    /// contained by this box, and the data must be valid for the specified type.
+    pub unsafe fn slice_from_count_mut<U>(&mut self, o: *mut U, count: usize) -> &mut [U] {
+        let base: *mut T = self.as_mut_ptr();
+        let ptr = self.sanitize_mut_ptr(o);
+        assert!(ptr >= base.cast());
+        assert!(count.saturating_mul(size_of::<U>()) <= self.size);
+        assert!(ptr.wrapping_add(count) <= base.cast::<u8>().add(self.size).cast());
+        from_raw_parts_mut(ptr, count)
+    }
+    /// Given a pointer to a variable sized array field and the length of the array in bytes,
+    /// returns a slice to the entire variable sized array.
+    /// # Safety
+    /// The slice as specified by `o` and `bytes` must be entirely within the allocation
+    /// contained by this box, and the data must be valid for the specified type.
+    pub unsafe fn slice_from_bytes<U>(&self, o: *const U, bytes: usize) -> &[U] {
+        let count = bytes / size_of::<U>();
+        self.slice_from_count(o, count)
+    }
+    /// Given a pointer to a variable sized array field and the length of the array in bytes,
+    /// returns a mutable slice to the entire variable sized array.
+    /// # Safety
+    /// The slice as specified by `o` and `bytes` must be entirely within the allocation
+    /// contained by this box, and the data must be valid for the specified type.
+    pub unsafe fn slice_from_bytes_mut<U>(&mut self, o: *mut U, bytes: usize) -> &mut [U] {
+        let count = bytes / size_of::<U>();
+        self.slice_from_count_mut(o, count)
+    }
+    /// Given a pointer to a variable sized array field and the size of the entire struct in bytes
+    /// including the size of the array, returns a slice to the entire variable sized array.
+    /// # Safety
+    /// The slice as specified by `o` and `total_bytes` must be entirely within the allocation
+    /// contained by this box, and the data must be valid for the specified type.
+    pub unsafe fn slice_from_total_bytes<U>(&self, o: *const U, total_bytes: usize) -> &[U] {
+        let bytes = total_bytes - (o as usize - self.as_ptr() as usize);
+        self.slice_from_bytes(o, bytes)
+    }
+    /// Given a pointer to a variable sized array field and the size of the entire struct in bytes
+    /// including the size of the array, returns a mutable slice to the entire variable sized
+    /// array.
+    /// # Safety
+    /// The slice as specified by `o` and `total_bytes` must be entirely within the allocation
+    /// contained by this box, and the data must be valid for the specified type.
+    pub unsafe fn slice_from_total_bytes_mut<U>(
+        &mut self,
+        o: *mut U,
+        total_bytes: usize,
+    ) -> &mut [U] {
+        let bytes = total_bytes - (o as usize - self.as_mut_ptr() as usize);
+        self.slice_from_bytes_mut(o, bytes)
+    }
+}
+impl<T, const N: usize> Drop for InlineVariableSizedBox<T, N> {
+    fn drop(&mut self) {
+        if let Storage::Heap(data) = self.storage {
+            let layout = Layout::from_size_align(self.size, align_of::<T>()).unwrap();
+            unsafe { dealloc(data.cast(), layout) }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_inline<T, const N: usize>(b: &InlineVariableSizedBox<T, N>) -> bool {
+        matches!(b.storage, Storage::Inline(_))
+    }
+
+    #[test]
+    fn stays_inline_when_it_fits() {
+        let b = InlineVariableSizedBox::<u8, 16>::new(8);
+        assert!(is_inline(&b));
+        assert_eq!(b.len(), 8);
+    }
+
+    #[test]
+    fn spills_to_heap_when_it_does_not_fit() {
+        let b = InlineVariableSizedBox::<u8, 4>::new(100);
+        assert!(!is_inline(&b));
+        assert_eq!(b.len(), 100);
+    }
+
+    #[test]
+    fn resize_spills_from_inline_to_heap_and_preserves_data() {
+        let mut b = InlineVariableSizedBox::<u8, 4>::new(4);
+        let ptr = b.as_mut_ptr();
+        unsafe {
+            b.slice_from_count_mut(ptr, 4)
+                .copy_from_slice(&[1, 2, 3, 4]);
+        }
+        b.resize(100);
+        assert!(!is_inline(&b));
+        let ptr = b.as_ptr();
+        assert_eq!(unsafe { b.slice_from_count(ptr, 4) }, &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn resize_moves_from_heap_back_to_inline_and_preserves_data() {
+        let mut b = InlineVariableSizedBox::<u8, 4>::new(100);
+        let ptr = b.as_mut_ptr();
+        unsafe {
+            b.slice_from_count_mut(ptr, 4)
+                .copy_from_slice(&[1, 2, 3, 4]);
+        }
+        b.resize(2);
+        assert!(is_inline(&b));
+        assert_eq!(b.len(), 2);
+        let ptr = b.as_ptr();
+        assert_eq!(unsafe { b.slice_from_count(ptr, 2) }, &[1, 2]);
+    }
+}
@@ -13,6 +13,7 @@ pub mod com;
 pub mod console;
 pub mod error;
 pub mod handle;
+pub mod inline_vsb;
 pub mod mutex;
 // pub mod perf;
 // pub mod pipe;
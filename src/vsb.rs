@@ -3,12 +3,44 @@
 // <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
 // All files in the project carrying such notice may not be copied, modified, or distributed
 // except according to those terms.
+use crate::error::AllocError;
 use std::{
     alloc::{alloc, alloc_zeroed, dealloc, handle_alloc_error, realloc, Layout},
     marker::PhantomData,
     mem::{align_of, size_of},
     slice::{from_raw_parts, from_raw_parts_mut},
 };
+/// A `zerocopy` byte-order wrapper (`U16<O>`, `U32<O>`, `U64<O>`) that can be read back as its
+/// native integer type, so [`VariableSizedBox::read_endian`] can be generic over both the
+/// integer width and the byte order.
+#[cfg(feature = "zerocopy")]
+pub trait NativeEndian: zerocopy::FromBytes {
+    /// The native integer type this wrapper converts to.
+    type Native;
+    /// Converts from the wrapper's fixed byte order to the host's native order.
+    fn get(&self) -> Self::Native;
+}
+#[cfg(feature = "zerocopy")]
+impl<O: zerocopy::byteorder::ByteOrder> NativeEndian for zerocopy::byteorder::U16<O> {
+    type Native = u16;
+    fn get(&self) -> u16 {
+        zerocopy::byteorder::U16::get(*self)
+    }
+}
+#[cfg(feature = "zerocopy")]
+impl<O: zerocopy::byteorder::ByteOrder> NativeEndian for zerocopy::byteorder::U32<O> {
+    type Native = u32;
+    fn get(&self) -> u32 {
+        zerocopy::byteorder::U32::get(*self)
+    }
+}
+#[cfg(feature = "zerocopy")]
+impl<O: zerocopy::byteorder::ByteOrder> NativeEndian for zerocopy::byteorder::U64<O> {
+    type Native = u64;
+    fn get(&self) -> u64 {
+        zerocopy::byteorder::U64::get(*self)
+    }
+}
 /// This is a smart pointer type for holding FFI types whose size varies.
 /// Most commonly this is with an array member as the last field whose size is specified
 /// by either another field, or an external source of information.
@@ -31,6 +63,21 @@ impl<T> VariableSizedBox<T> {
             pd: PhantomData,
         }
     }
+    /// Fallible version of [`VariableSizedBox::new`].
+    /// The size is specified in bytes. The data is uninitialized.
+    /// Returns `Err(AllocError)` instead of aborting if the allocation fails.
+    pub fn try_new(size: usize) -> Result<VariableSizedBox<T>, AllocError> {
+        let layout = Layout::from_size_align(size, align_of::<T>()).unwrap();
+        let data = unsafe { alloc(layout) };
+        if data.is_null() {
+            return Err(AllocError);
+        }
+        Ok(VariableSizedBox {
+            size,
+            data: data.cast(),
+            pd: PhantomData,
+        })
+    }
     /// The size is specified in bytes. The data is zeroed.
     pub fn zeroed(size: usize) -> VariableSizedBox<T> {
         let layout = Layout::from_size_align(size, align_of::<T>()).unwrap();
@@ -44,6 +91,21 @@ impl<T> VariableSizedBox<T> {
             pd: PhantomData,
         }
     }
+    /// Fallible version of [`VariableSizedBox::zeroed`].
+    /// The size is specified in bytes. The data is zeroed.
+    /// Returns `Err(AllocError)` instead of aborting if the allocation fails.
+    pub fn try_zeroed(size: usize) -> Result<VariableSizedBox<T>, AllocError> {
+        let layout = Layout::from_size_align(size, align_of::<T>()).unwrap();
+        let data = unsafe { alloc_zeroed(layout) };
+        if data.is_null() {
+            return Err(AllocError);
+        }
+        Ok(VariableSizedBox {
+            size,
+            data: data.cast(),
+            pd: PhantomData,
+        })
+    }
     /// Use this to get a pointer to pass to FFI functions.
     pub fn as_ptr(&self) -> *const T {
         self.data
@@ -66,7 +128,7 @@ impl<T> VariableSizedBox<T> {
     }
     /// The size is specified in bytes.
     /// If this grows the allocation, the extra bytes will be uninitialized.
-    /// I wish I could provide a zeroed alternative but Rust's stable allocators are lacking.
+    /// See [`VariableSizedBox::resize_zeroed`] if the grown tail needs to read as zero.
     pub fn resize(&mut self, size: usize) {
         let layout = Layout::from_size_align(self.size, align_of::<T>()).unwrap();
         let data = unsafe { realloc(self.data.cast(), layout, size) };
@@ -76,6 +138,35 @@ impl<T> VariableSizedBox<T> {
         self.data = data.cast();
         self.size = size;
     }
+    /// The size is specified in bytes.
+    /// If this grows the allocation, the extra bytes beyond the previous length are zeroed.
+    pub fn resize_zeroed(&mut self, size: usize) {
+        let old_size = self.size;
+        self.resize(size);
+        if size > old_size {
+            unsafe {
+                self.data
+                    .cast::<u8>()
+                    .add(old_size)
+                    .write_bytes(0, size - old_size);
+            }
+        }
+    }
+    /// Fallible version of [`VariableSizedBox::resize`].
+    /// The size is specified in bytes.
+    /// If this grows the allocation, the extra bytes will be uninitialized.
+    /// On failure, `Err(AllocError)` is returned and the existing allocation is left untouched,
+    /// matching the contract of the underlying `realloc`.
+    pub fn try_resize(&mut self, size: usize) -> Result<(), AllocError> {
+        let layout = Layout::from_size_align(self.size, align_of::<T>()).unwrap();
+        let data = unsafe { realloc(self.data.cast(), layout, size) };
+        if data.is_null() {
+            return Err(AllocError);
+        }
+        self.data = data.cast();
+        self.size = size;
+        Ok(())
+    }
     /// The length of the allocation specified in bytes.
     pub fn len(&self) -> usize {
         self.size
@@ -108,6 +199,23 @@ impl<T> VariableSizedBox<T> {
         assert!(ptr.wrapping_add(count) <= self.data.cast::<u8>().add(self.size).cast());
         from_raw_parts(ptr, count)
     }
+    /// Given a byte offset into the allocation and the length of the array in elements, returns
+    /// a slice to the variable sized array starting at that offset.
+    /// Unlike [`VariableSizedBox::slice_from_count`], this is safe: the offset and count are
+    /// validated against the allocation internally rather than trusted from a caller-supplied
+    /// pointer, and `U: FromBytes` proves every bit pattern is a valid `U`.
+    #[cfg(feature = "zerocopy")]
+    pub fn slice_from_count_checked<U: zerocopy::FromBytes>(
+        &self,
+        offset: usize,
+        count: usize,
+    ) -> &[U] {
+        assert_eq!((self.data as usize).wrapping_add(offset) % align_of::<U>(), 0);
+        assert!(offset <= self.size);
+        assert!(count.saturating_mul(size_of::<U>()) <= self.size - offset);
+        let ptr = unsafe { self.data.cast::<u8>().add(offset).cast::<U>() };
+        unsafe { from_raw_parts(ptr, count) }
+    }
     /// Given a pointer to a variable sized array field and the length of the array in elements,
     /// returns a mutable slice to the entire variable sized array.
     /// # Safety
@@ -120,6 +228,23 @@ impl<T> VariableSizedBox<T> {
         assert!(ptr.wrapping_add(count) <= self.data.cast::<u8>().add(self.size).cast());
         from_raw_parts_mut(ptr, count)
     }
+    /// Given a byte offset into the allocation and the length of the array in elements, returns
+    /// a mutable slice to the variable sized array starting at that offset.
+    /// Unlike [`VariableSizedBox::slice_from_count_mut`], this is safe: the offset and count are
+    /// validated against the allocation internally rather than trusted from a caller-supplied
+    /// pointer, and `U: FromBytes` proves every bit pattern is a valid `U`.
+    #[cfg(feature = "zerocopy")]
+    pub fn slice_from_count_mut_checked<U: zerocopy::FromBytes>(
+        &mut self,
+        offset: usize,
+        count: usize,
+    ) -> &mut [U] {
+        assert_eq!((self.data as usize).wrapping_add(offset) % align_of::<U>(), 0);
+        assert!(offset <= self.size);
+        assert!(count.saturating_mul(size_of::<U>()) <= self.size - offset);
+        let ptr = unsafe { self.data.cast::<u8>().add(offset).cast::<U>() };
+        unsafe { from_raw_parts_mut(ptr, count) }
+    }
     /// Given a pointer to a variable sized array field and the length of the array in bytes,
     /// returns a slice to the entire variable sized array.
     /// # Safety
@@ -129,6 +254,19 @@ impl<T> VariableSizedBox<T> {
         let count = bytes / size_of::<U>();
         self.slice_from_count(o, count)
     }
+    /// Given a byte offset into the allocation and the length of the array in bytes, returns a
+    /// slice to the variable sized array starting at that offset.
+    /// Unlike [`VariableSizedBox::slice_from_bytes`], this is safe; see
+    /// [`VariableSizedBox::slice_from_count_checked`].
+    #[cfg(feature = "zerocopy")]
+    pub fn slice_from_bytes_checked<U: zerocopy::FromBytes>(
+        &self,
+        offset: usize,
+        bytes: usize,
+    ) -> &[U] {
+        let count = bytes / size_of::<U>();
+        self.slice_from_count_checked(offset, count)
+    }
     /// Given a pointer to a variable sized array field and the length of the array in bytes,
     /// returns a mutable slice to the entire variable sized array.
     /// # Safety
@@ -138,6 +276,44 @@ impl<T> VariableSizedBox<T> {
         let count = bytes / size_of::<U>();
         self.slice_from_count_mut(o, count)
     }
+    /// Given a byte offset into the allocation and the length of the array in bytes, returns a
+    /// mutable slice to the variable sized array starting at that offset.
+    /// Unlike [`VariableSizedBox::slice_from_bytes_mut`], this is safe; see
+    /// [`VariableSizedBox::slice_from_count_mut_checked`].
+    #[cfg(feature = "zerocopy")]
+    pub fn slice_from_bytes_mut_checked<U: zerocopy::FromBytes>(
+        &mut self,
+        offset: usize,
+        bytes: usize,
+    ) -> &mut [U] {
+        let count = bytes / size_of::<U>();
+        self.slice_from_count_mut_checked(offset, count)
+    }
+    /// Given a byte offset into the allocation and the array's length in elements, reads it in
+    /// the byte order fixed by `W` (one of `zerocopy`'s `U16<O>`/`U32<O>`/`U64<O>` wrappers) and
+    /// returns the values converted to the host's native order.
+    /// Performs the same bounds checks as [`VariableSizedBox::slice_from_count_checked`].
+    #[cfg(feature = "zerocopy")]
+    pub fn read_endian<W: NativeEndian>(&self, offset: usize, count: usize) -> Vec<W::Native> {
+        self.slice_from_count_checked::<W>(offset, count)
+            .iter()
+            .map(NativeEndian::get)
+            .collect()
+    }
+    /// Given a byte offset into the allocation and the array's length in elements, reads it as
+    /// big-endian `u32`s and returns the values converted to the host's native order.
+    #[cfg(feature = "zerocopy")]
+    pub fn read_be_u32_array(&self, offset: usize, count: usize) -> Vec<u32> {
+        self.read_endian::<zerocopy::byteorder::U32<zerocopy::byteorder::BigEndian>>(offset, count)
+    }
+    /// Given a byte offset into the allocation and the array's length in elements, reads it as
+    /// little-endian `u32`s and returns the values converted to the host's native order.
+    #[cfg(feature = "zerocopy")]
+    pub fn read_le_u32_array(&self, offset: usize, count: usize) -> Vec<u32> {
+        self.read_endian::<zerocopy::byteorder::U32<zerocopy::byteorder::LittleEndian>>(
+            offset, count,
+        )
+    }
     /// Given a pointer to a variable sized array field and the size of the entire struct in bytes
     /// including the size of the array, returns a slice to the entire variable sized array.
     /// # Safety
@@ -147,6 +323,19 @@ impl<T> VariableSizedBox<T> {
         let bytes = total_bytes - (o as usize - self.data as usize);
         self.slice_from_bytes(o, bytes)
     }
+    /// Given a byte offset into the allocation and the size of the entire struct in bytes
+    /// including the size of the array, returns a slice to the variable sized array starting at
+    /// that offset.
+    /// Unlike [`VariableSizedBox::slice_from_total_bytes`], this is safe; see
+    /// [`VariableSizedBox::slice_from_count_checked`].
+    #[cfg(feature = "zerocopy")]
+    pub fn slice_from_total_bytes_checked<U: zerocopy::FromBytes>(
+        &self,
+        offset: usize,
+        total_bytes: usize,
+    ) -> &[U] {
+        self.slice_from_bytes_checked(offset, total_bytes - offset)
+    }
     /// Given a pointer to a variable sized array field and the size of the entire struct in bytes
     /// including the size of the array, returns a mutable slice to the entire variable sized
     /// array.
@@ -161,6 +350,19 @@ impl<T> VariableSizedBox<T> {
         let bytes = total_bytes - (o as usize - self.data as usize);
         self.slice_from_bytes_mut(o, bytes)
     }
+    /// Given a byte offset into the allocation and the size of the entire struct in bytes
+    /// including the size of the array, returns a mutable slice to the variable sized array
+    /// starting at that offset.
+    /// Unlike [`VariableSizedBox::slice_from_total_bytes_mut`], this is safe; see
+    /// [`VariableSizedBox::slice_from_count_mut_checked`].
+    #[cfg(feature = "zerocopy")]
+    pub fn slice_from_total_bytes_mut_checked<U: zerocopy::FromBytes>(
+        &mut self,
+        offset: usize,
+        total_bytes: usize,
+    ) -> &mut [U] {
+        self.slice_from_bytes_mut_checked(offset, total_bytes - offset)
+    }
 }
 impl<T> Drop for VariableSizedBox<T> {
     fn drop(&mut self) {
@@ -168,3 +370,41 @@ impl<T> Drop for VariableSizedBox<T> {
         unsafe { dealloc(self.data.cast(), layout) }
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_new_try_zeroed_try_resize_happy_path() {
+        let mut b = VariableSizedBox::<u8>::try_new(4).unwrap();
+        assert_eq!(b.len(), 4);
+        let z = VariableSizedBox::<u8>::try_zeroed(4).unwrap();
+        assert_eq!(unsafe { z.slice_from_count(z.as_ptr(), 4) }, &[0u8; 4]);
+        b.try_resize(8).unwrap();
+        assert_eq!(b.len(), 8);
+    }
+
+    #[test]
+    fn resize_zeroed_only_zeroes_the_grown_tail() {
+        let mut b = VariableSizedBox::<u8>::zeroed(4);
+        let ptr = b.as_mut_ptr();
+        unsafe {
+            b.slice_from_count_mut(ptr, 4)
+                .copy_from_slice(&[1, 2, 3, 4]);
+        }
+        b.resize_zeroed(8);
+        let ptr = b.as_ptr();
+        assert_eq!(
+            unsafe { b.slice_from_count(ptr, 8) },
+            &[1, 2, 3, 4, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "zerocopy")]
+    #[should_panic]
+    fn slice_from_count_checked_panics_on_out_of_bounds_offset() {
+        let b = VariableSizedBox::<u8>::zeroed(16);
+        let _ = b.slice_from_count_checked::<u8>(usize::MAX - 3, 0);
+    }
+}